@@ -0,0 +1,79 @@
+//! Throws arbitrary sequences of `create`/`swap`/`add_liquidity`/`remove_liquidity`/`on_finalize`
+//! calls at the pallet's mock runtime and checks `mock::assert_invariants` after every step.
+//!
+//! Run with `cargo hfuzz run create_swap_finalize` from this directory.
+
+use arbitrary::Arbitrary;
+use honggfuzz::fuzz;
+
+use fixed_swap::{mock::*, PoolKind};
+
+#[derive(Debug, Arbitrary)]
+enum Op {
+	Create {
+		token0: u8,
+		token1: u8,
+		total0: u64,
+		total1: u64,
+		duration: u16,
+		constant_product: bool,
+		fee_bps: u16,
+		max_alloc: Option<u64>,
+	},
+	Swap { pool_id: u32, buyer: u8, amount1: u64, min_amount0: u64 },
+	AddLiquidity { pool_id: u32, provider: u8, amount0: u64, amount1: u64 },
+	RemoveLiquidity { pool_id: u32, provider: u8, shares: u64 },
+	Finalize { block: u16 },
+}
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+	ops: Vec<Op>,
+}
+
+fn main() {
+	loop {
+		fuzz!(|input: Input| {
+			new_test_ext().execute_with(|| {
+				for op in input.ops {
+					match op {
+						Op::Create {
+							token0, token1, total0, total1, duration, constant_product, fee_bps, max_alloc,
+						} => {
+							let kind = if constant_product { PoolKind::ConstantProduct } else { PoolKind::Fixed };
+							let _ = FixedSwap::create(
+								Origin::signed(0),
+								b"fuzz".to_vec(),
+								token0 as TokenId,
+								token1 as TokenId,
+								total0 as Balance,
+								total1 as Balance,
+								duration as BlockNumber,
+								kind,
+								fee_bps as u32 % 10_001,
+								max_alloc.map(|a| a as Balance),
+							);
+						},
+						Op::Swap { pool_id, buyer, amount1, min_amount0 } => {
+							let _ = FixedSwap::swap(
+								Origin::signed(buyer as u64), pool_id, amount1 as Balance, min_amount0 as Balance,
+							);
+						},
+						Op::AddLiquidity { pool_id, provider, amount0, amount1 } => {
+							let _ = FixedSwap::add_liquidity(
+								Origin::signed(provider as u64), pool_id, amount0 as Balance, amount1 as Balance,
+							);
+						},
+						Op::RemoveLiquidity { pool_id, provider, shares } => {
+							let _ = FixedSwap::remove_liquidity(Origin::signed(provider as u64), pool_id, shares as Balance);
+						},
+						Op::Finalize { block } => {
+							FixedSwap::on_finalize(block as BlockNumber);
+						},
+					}
+					assert_invariants();
+				}
+			});
+		});
+	}
+}
@@ -0,0 +1,22 @@
+//! Default weights for this pallet's extrinsics, used when a runtime doesn't supply its own
+//! `WeightInfo` (e.g. in tests, where `Config::WeightInfo = ()`).
+
+use frame_support::weights::Weight;
+
+impl crate::WeightInfo for () {
+	fn create() -> Weight {
+		10_000
+	}
+	fn swap() -> Weight {
+		10_000
+	}
+	fn add_liquidity() -> Weight {
+		10_000
+	}
+	fn remove_liquidity() -> Weight {
+		10_000
+	}
+	fn on_finalize(count: u32) -> Weight {
+		10_000 * count as Weight
+	}
+}
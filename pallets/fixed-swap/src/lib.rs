@@ -1,22 +1,48 @@
 #![cfg_attr(not(feature = "std"), no_std)]
 
 use codec::{Encode, Decode};
+use primitive_types::U256;
 use sp_runtime::{
 	RuntimeDebug,
 	traits::{
-		MaybeSerializeDeserialize, Member, AtLeast32BitUnsigned, Saturating, Zero,
+		AccountIdConversion, Convert, MaybeSerializeDeserialize, Member, AtLeast32BitUnsigned,
+		SaturatedConversion, Saturating, Zero,
 	}
 };
 use sp_std::{fmt::Debug, prelude::Vec};
 use frame_support::{
-	ensure, decl_module, decl_storage, decl_event, decl_error,
-	dispatch::DispatchResult, weights::Weight, Parameter,
+	ensure, decl_module, decl_storage, decl_event, decl_error, transactional,
+	dispatch::DispatchResult, traits::Get, weights::Weight, Parameter, PalletId,
 };
 use frame_system::ensure_signed;
-use orml_traits::{MultiCurrency, MultiReservableCurrency};
+use orml_traits::MultiCurrency;
 
 mod default_weight;
 
+#[cfg(any(test, feature = "fuzzing"))]
+pub mod mock;
+#[cfg(test)]
+mod tests;
+
+/// LP shares permanently locked from the first deposit into a pool, to deter the classic
+/// first-depositor share-price inflation attack.
+const MIN_LIQUIDITY: u32 = 1_000;
+
+/// The pricing rule used to convert `amount1` into `amount0` on a swap.
+#[derive(Clone, Encode, Decode, Eq, PartialEq, RuntimeDebug)]
+pub enum PoolKind {
+	/// A flat exchange ratio fixed at creation: `amount0 = amount1 * total0 / total1`.
+	Fixed,
+	/// A Uniswap-V2-style pool where `total0`/`total1` are live reserves that reprice on every swap.
+	ConstantProduct,
+}
+
+impl Default for PoolKind {
+	fn default() -> Self {
+		PoolKind::Fixed
+	}
+}
+
 #[derive(Clone, Encode, Decode, Eq, PartialEq, RuntimeDebug, Default)]
 pub struct PoolDetails<
 	AccountId: Encode + Decode + Clone + Debug + Eq + PartialEq + Default,
@@ -34,11 +60,18 @@ pub struct PoolDetails<
 	swapped1: Balance,
 	duration: BlockNumber,
 	start_at: BlockNumber,
+	kind: PoolKind,
+	/// Swap fee in basis points, only applied for `PoolKind::ConstantProduct` pools.
+	fee_bps: u32,
+	/// Caps the cumulative token0 a single account may receive from this pool, if set.
+	max_alloc_per_account: Option<Balance>,
 }
 
 pub trait WeightInfo {
 	fn create() -> Weight;
 	fn swap() -> Weight;
+	fn add_liquidity() -> Weight;
+	fn remove_liquidity() -> Weight;
 	fn on_finalize(count: u32) -> Weight;
 }
 
@@ -53,9 +86,16 @@ pub trait Config: frame_system::Config {
 	/// The type of token identifier.
 	type TokenId: Member + Parameter + Default + Copy + MaybeSerializeDeserialize;
 
-	/// The currency mechanism.
-	type Currency: MultiCurrency<Self::AccountId, CurrencyId = Self::TokenId, Balance = Self::Balance>
-		+ MultiReservableCurrency<Self::AccountId>;
+	/// The currency mechanism. Plug in `orml_currencies::Module` (or any other union adapter
+	/// over `MultiCurrency`) to let a `TokenId` resolve to the chain's native balances pallet
+	/// instead of an `orml_tokens` asset, so pools can trade native currency on either side.
+	type Currency: MultiCurrency<Self::AccountId, CurrencyId = Self::TokenId, Balance = Self::Balance>;
+
+	/// The pallet id, used to derive each pool's sovereign account.
+	type PalletId: Get<PalletId>;
+
+	/// Derives a pool's LP share token id from its pool id.
+	type LpTokenId: Convert<Self::PoolId, Self::TokenId>;
 
 	/// Weight information for extrinsics in this module.
 	type WeightInfo: WeightInfo;
@@ -67,13 +107,17 @@ decl_storage! {
 		NextPoolId get(fn next_pool_id): T::PoolId;
 
 		/// Details of a pool.
-		Pool: map hasher(blake2_128_concat) T::PoolId
+		Pool get(fn pools): map hasher(blake2_128_concat) T::PoolId
 			=> PoolDetails<T::AccountId, T::Balance, T::BlockNumber, T::TokenId>;
 
 		/// Swap records by a pool and an account.
-		Swap: double_map hasher(blake2_128_concat) T::PoolId, hasher(blake2_128_concat) T::AccountId
+		Swap get(fn swaps): double_map hasher(blake2_128_concat) T::PoolId, hasher(blake2_128_concat) T::AccountId
 			=> (T::Balance, T::Balance);
 
+		/// Total LP shares issued for a pool. Individual holdings are the LP token balance
+		/// (see `Config::LpTokenId`) rather than a separate per-account map.
+		Shares get(fn shares): map hasher(blake2_128_concat) T::PoolId => T::Balance;
+
 		/// The end block number of a pool
 		PoolEndAt get(fn pool_end_at):
 			double_map hasher(twox_64_concat) T::BlockNumber, hasher(twox_64_concat) T::PoolId
@@ -85,10 +129,15 @@ decl_event!(
 	pub enum Event<T> where
 		<T as frame_system::Config>::AccountId,
 		<T as Config>::PoolId,
+		<T as Config>::Balance,
 	{
 		PoolCreated(PoolId, AccountId),
 		PoolSwapped(PoolId, AccountId),
 		PoolClosed(PoolId),
+		/// A provider deposited `Balance` of token0 and was minted `Balance` LP shares.
+		LiquidityAdded(PoolId, AccountId, Balance, Balance),
+		/// A provider burned `Balance` LP shares and was paid out `Balance` of token0 and `Balance` of token1.
+		LiquidityRemoved(PoolId, AccountId, Balance, Balance, Balance),
 	}
 );
 
@@ -96,6 +145,25 @@ decl_error! {
 	pub enum Error for Module<T: Config> {
 		InvalidDuration,
 		PoolExpired,
+		InvalidFee,
+		/// A constant-product swap computed a zero `amount0`, e.g. because `amount1` was too small.
+		InsufficientOutputAmount,
+		/// `total0` at creation did not exceed `MIN_LIQUIDITY`, so no shares could be minted.
+		InsufficientLiquidityMinted,
+		/// The deposit or the pool's shares were too small to mint a non-zero number of shares.
+		ZeroLiquidityMinted,
+		/// Burning the given number of shares would pay out zero of both tokens.
+		ZeroLiquidityBurned,
+		/// The swap's `amount0` fell below the caller's `min_amount0` floor.
+		SlippageExceeded,
+		/// The buyer's cumulative token0 allocation from this pool would exceed its cap.
+		AllocationExceeded,
+		/// `add_liquidity` only supports `PoolKind::ConstantProduct`, whose
+		/// `total0`/`total1` are live reserves; a `Fixed` pool's rate must stay fixed at creation.
+		OnlyConstantProductPools,
+		/// `remove_liquidity` is only allowed once the pool has finished selling, so a provider
+		/// can't drain the reserve a `Fixed` pool is actively selling out from under buyers.
+		PoolNotClosed,
 	}
 }
 
@@ -114,15 +182,30 @@ decl_module! {
 			total0: T::Balance,
 			total1: T::Balance,
 			duration: T::BlockNumber,
+			kind: PoolKind,
+			fee_bps: u32,
+			max_alloc_per_account: Option<T::Balance>,
 		) {
 			ensure!(duration > Zero::zero(), Error::<T>::InvalidDuration);
+			ensure!(fee_bps <= 10_000, Error::<T>::InvalidFee);
+			ensure!(total0 > MIN_LIQUIDITY.into(), Error::<T>::InsufficientLiquidityMinted);
 
 			let creator = ensure_signed(origin)?;
 			let pool_id: T::PoolId = NextPoolId::<T>::get();
 			let start_at = <frame_system::Module<T>>::block_number();
 			let end_at = start_at.saturating_add(duration);
 
-			T::Currency::reserve(token0, &creator, total0)?;
+			let pool_account = Self::pool_account_id(pool_id);
+			T::Currency::transfer(token0, &creator, &pool_account, total0)?;
+
+			// The creator is the pool's first liquidity provider; lock `MIN_LIQUIDITY` shares
+			// forever to deter the first-depositor share-price inflation attack.
+			let lp_token = T::LpTokenId::convert(pool_id);
+			let locked_account = Self::locked_liquidity_account(pool_id);
+			T::Currency::deposit(lp_token, &locked_account, MIN_LIQUIDITY.into())?;
+			let creator_shares = total0.saturating_sub(MIN_LIQUIDITY.into());
+			T::Currency::deposit(lp_token, &creator, creator_shares)?;
+			Shares::<T>::insert(pool_id, total0);
 
 			Pool::<T>::insert(pool_id, PoolDetails {
 				name,
@@ -135,6 +218,9 @@ decl_module! {
 				swapped1: Zero::zero(),
 				duration,
 				start_at,
+				kind,
+				fee_bps,
+				max_alloc_per_account,
 			});
 			PoolEndAt::<T>::insert(end_at, pool_id, ());
 			NextPoolId::<T>::put(pool_id.saturating_add(1u32.into()));
@@ -143,36 +229,148 @@ decl_module! {
 		}
 
 		#[weight = T::WeightInfo::swap()]
+		#[transactional]
 		pub fn swap(
 			origin,
 			pool_id: T::PoolId,
 			amount1: T::Balance,
+			min_amount0: T::Balance,
 		) {
 			let buyer = ensure_signed(origin)?;
 
 			Pool::<T>::try_mutate(pool_id, |pool| -> DispatchResult {
 				let now = <frame_system::Module<T>>::block_number();
-				ensure!(pool.start_at.saturating_add(pool.duration) < now, Error::<T>::PoolExpired);
+				ensure!(now < pool.start_at.saturating_add(pool.duration), Error::<T>::PoolExpired);
+
+				let amount0 = match pool.kind {
+					PoolKind::Fixed => amount1.saturating_mul(pool.total0) / pool.total1,
+					PoolKind::ConstantProduct => {
+						let dy = Self::constant_product_output(
+							pool.total0, pool.total1, amount1, pool.fee_bps,
+						).ok_or(Error::<T>::InsufficientOutputAmount)?;
+						pool.total0 = pool.total0.saturating_sub(dy);
+						pool.total1 = pool.total1.saturating_add(amount1);
+						dy
+					},
+				};
+				ensure!(amount0 >= min_amount0, Error::<T>::SlippageExceeded);
+
+				// Accumulate the buyer's running total, not multiply it, or the allocation cap
+				// below could never be reached.
+				let current_swap = Swap::<T>::get(pool_id, &buyer);
+				let swap0 = current_swap.0.saturating_add(amount0);
+				let swap1 = current_swap.1.saturating_add(amount1);
+				if let Some(max_alloc) = pool.max_alloc_per_account {
+					ensure!(swap0 <= max_alloc, Error::<T>::AllocationExceeded);
+				}
 
-				let amount0 = amount1.saturating_mul(pool.total0) / pool.total1;
 				pool.swapped0 = pool.swapped0.saturating_add(amount0);
 				pool.swapped1 = pool.swapped1.saturating_add(amount1);
 
-				T::Currency::unreserve(pool.token0, &pool.creator, amount0);
-				T::Currency::transfer(pool.token0, &pool.creator, &buyer, amount0)?;
-				T::Currency::transfer(pool.token1, &buyer, &pool.creator, amount0)?;
+				let pool_account = Self::pool_account_id(pool_id);
+				T::Currency::transfer(pool.token0, &pool_account, &buyer, amount0)?;
+				T::Currency::transfer(pool.token1, &buyer, &pool_account, amount1)?;
 
-				Swap::<T>::try_mutate(pool_id, &buyer, |swap| -> DispatchResult {
-					swap.0 = swap.0.saturating_mul(amount0);
-					swap.1 = swap.1.saturating_mul(amount1);
-					Ok(())
-				})?;
+				Swap::<T>::insert(pool_id, &buyer, (swap0, swap1));
 
 				Self::deposit_event(RawEvent::PoolSwapped(pool_id, buyer));
 				Ok(())
 			})?;
 		}
 
+		/// Deposit `amount0` of a pool's token0 and `amount1` of its token1, receiving LP shares
+		/// proportional to the pool's current reserves of both. Only `PoolKind::ConstantProduct`
+		/// pools accept liquidity after creation; a `Fixed` pool's rate must stay fixed.
+		#[weight = T::WeightInfo::add_liquidity()]
+		#[transactional]
+		pub fn add_liquidity(
+			origin,
+			pool_id: T::PoolId,
+			amount0: T::Balance,
+			amount1: T::Balance,
+		) {
+			let who = ensure_signed(origin)?;
+			ensure!(amount0 > Zero::zero() && amount1 > Zero::zero(), Error::<T>::ZeroLiquidityMinted);
+
+			Pool::<T>::try_mutate(pool_id, |pool| -> DispatchResult {
+				ensure!(pool.kind == PoolKind::ConstantProduct, Error::<T>::OnlyConstantProductPools);
+
+				let pool_account = Self::pool_account_id(pool_id);
+				let reserve0 = T::Currency::free_balance(pool.token0, &pool_account);
+				let reserve1 = T::Currency::free_balance(pool.token1, &pool_account);
+				let total_shares = Shares::<T>::get(pool_id);
+
+				// Price the deposit against both reserves and mint the more conservative of the
+				// two, so a provider can't buy a disproportionate claim on one side's reserve
+				// (and the token1 proceeds already accumulated for existing LPs) with the other.
+				let shares0 = Self::mul_div(amount0, total_shares, reserve0)
+					.ok_or(Error::<T>::ZeroLiquidityMinted)?;
+				let shares1 = Self::mul_div(amount1, total_shares, reserve1)
+					.ok_or(Error::<T>::ZeroLiquidityMinted)?;
+				let shares = shares0.min(shares1);
+				ensure!(shares > Zero::zero(), Error::<T>::ZeroLiquidityMinted);
+
+				T::Currency::transfer(pool.token0, &who, &pool_account, amount0)?;
+				T::Currency::transfer(pool.token1, &who, &pool_account, amount1)?;
+				let lp_token = T::LpTokenId::convert(pool_id);
+				T::Currency::deposit(lp_token, &who, shares)?;
+
+				pool.total0 = pool.total0.saturating_add(amount0);
+				pool.total1 = pool.total1.saturating_add(amount1);
+				Shares::<T>::insert(pool_id, total_shares.saturating_add(shares));
+
+				Self::deposit_event(RawEvent::LiquidityAdded(pool_id, who, amount0, shares));
+				Ok(())
+			})?;
+		}
+
+		/// Burn `shares` LP shares and receive a proportional share of the pool's remaining
+		/// token0 reserve and collected token1 proceeds. Only allowed once the pool has closed,
+		/// so a provider can't redeem while a `Fixed` pool is still selling out of its reserve.
+		#[weight = T::WeightInfo::remove_liquidity()]
+		#[transactional]
+		pub fn remove_liquidity(
+			origin,
+			pool_id: T::PoolId,
+			shares: T::Balance,
+		) {
+			let who = ensure_signed(origin)?;
+			ensure!(shares > Zero::zero(), Error::<T>::ZeroLiquidityBurned);
+
+			Pool::<T>::try_mutate(pool_id, |pool| -> DispatchResult {
+				let now = <frame_system::Module<T>>::block_number();
+				ensure!(now >= pool.start_at.saturating_add(pool.duration), Error::<T>::PoolNotClosed);
+
+				let total_shares = Shares::<T>::get(pool_id);
+				let pool_account = Self::pool_account_id(pool_id);
+				let reserve0 = T::Currency::free_balance(pool.token0, &pool_account);
+				let reserve1 = T::Currency::free_balance(pool.token1, &pool_account);
+
+				let payout0 = Self::mul_div(shares, reserve0, total_shares).unwrap_or_else(Zero::zero);
+				let payout1 = Self::mul_div(shares, reserve1, total_shares).unwrap_or_else(Zero::zero);
+				ensure!(payout0 > Zero::zero() || payout1 > Zero::zero(), Error::<T>::ZeroLiquidityBurned);
+
+				let lp_token = T::LpTokenId::convert(pool_id);
+				T::Currency::withdraw(lp_token, &who, shares)?;
+				Shares::<T>::insert(pool_id, total_shares.saturating_sub(shares));
+
+				if payout0 > Zero::zero() {
+					T::Currency::transfer(pool.token0, &pool_account, &who, payout0)?;
+					// A `Fixed` pool's `total0` is the deposit its rate was fixed against, not a
+					// live reserve, so it is left as it is.
+					if pool.kind == PoolKind::ConstantProduct {
+						pool.total0 = pool.total0.saturating_sub(payout0);
+					}
+				}
+				if payout1 > Zero::zero() {
+					T::Currency::transfer(pool.token1, &pool_account, &who, payout1)?;
+				}
+
+				Self::deposit_event(RawEvent::LiquidityRemoved(pool_id, who, shares, payout0, payout1));
+				Ok(())
+			})?;
+		}
+
 		fn on_initialize(now: T::BlockNumber) -> Weight {
 			T::WeightInfo::on_finalize(PoolEndAt::<T>::iter_prefix(&now).count() as u32)
 		}
@@ -184,14 +382,60 @@ decl_module! {
 }
 
 impl<T: Config> Module<T> {
+	/// Constant-product swap output for reserves `r0`/`r1` and an input `dx` of token1, net of
+	/// `fee_bps`. Math is widened to `U256` so the intermediate product can't overflow before the
+	/// final divide. Returns `None` if `dy` would round down to zero.
+	fn constant_product_output(
+		r0: T::Balance,
+		r1: T::Balance,
+		dx: T::Balance,
+		fee_bps: u32,
+	) -> Option<T::Balance> {
+		let r0 = U256::from(r0.saturated_into::<u128>());
+		let r1 = U256::from(r1.saturated_into::<u128>());
+		let dx = U256::from(dx.saturated_into::<u128>());
+
+		let dx_with_fee = dx.saturating_mul(U256::from(10_000u32 - fee_bps)) / U256::from(10_000u32);
+		let denominator = r1.saturating_add(dx_with_fee);
+		if denominator.is_zero() {
+			return None;
+		}
+		let dy = r0.saturating_mul(dx_with_fee) / denominator;
+		if dy.is_zero() {
+			return None;
+		}
+
+		Some(dy.as_u128().saturated_into())
+	}
+
 	fn on_finalize(now: T::BlockNumber) {
+		// Unsold token0 and collected token1 stay in the pool account: liquidity providers
+		// (the creator included, via the shares minted in `create`) redeem their share of both
+		// through `remove_liquidity` rather than having it auto-paid to the creator alone.
 		for (pool_id, _) in PoolEndAt::<T>::drain_prefix(&now) {
-			let pool = Pool::<T>::get(pool_id);
-			let un_swapped0 = pool.total0.saturating_sub(pool.swapped0);
-			if un_swapped0 > Zero::zero() {
-				T::Currency::unreserve(pool.token0, &pool.creator, un_swapped0);
-			}
 			Self::deposit_event(RawEvent::PoolClosed(pool_id));
 		}
 	}
+
+	/// Proportion `a * b / c`, widened to `U256` so the intermediate product can't overflow
+	/// before the final divide. Returns `None` if `c` is zero.
+	fn mul_div(a: T::Balance, b: T::Balance, c: T::Balance) -> Option<T::Balance> {
+		let c = U256::from(c.saturated_into::<u128>());
+		if c.is_zero() {
+			return None;
+		}
+		let a = U256::from(a.saturated_into::<u128>());
+		let b = U256::from(b.saturated_into::<u128>());
+		Some((a.saturating_mul(b) / c).as_u128().saturated_into())
+	}
+
+	/// The sovereign account a pool's reserved `token0` is custodied in.
+	fn pool_account_id(pool_id: T::PoolId) -> T::AccountId {
+		T::PalletId::get().into_sub_account(pool_id)
+	}
+
+	/// The account the first depositor's permanently-locked `MIN_LIQUIDITY` shares are minted to.
+	fn locked_liquidity_account(pool_id: T::PoolId) -> T::AccountId {
+		T::PalletId::get().into_sub_account((b"locked__", pool_id))
+	}
 }
@@ -1,11 +1,11 @@
-#![cfg(test)]
+#![cfg(any(test, feature = "fuzzing"))]
 
-use crate::{Module, Config};
-use orml_traits::parameter_type_with_key;
+use crate::{Module, Config, PoolKind};
+use orml_traits::{parameter_type_with_key, MultiCurrency};
 use sp_core::H256;
 use frame_support::{impl_outer_origin, parameter_types};
 use sp_runtime::{
-	traits::{BlakeTwo256, IdentityLookup}, testing::Header,
+	traits::{BlakeTwo256, Convert, IdentityLookup}, testing::Header,
 };
 use frame_system as system;
 
@@ -97,16 +97,33 @@ impl orml_currencies::Config for Runtime {
 }
 pub type Currencies = orml_currencies::Module<Runtime>;
 
+parameter_types! {
+	pub const FixedSwapPalletId: frame_support::PalletId = frame_support::PalletId(*b"py/fxswp");
+}
+
+/// LP share token ids live in a namespace above the token ids real assets use in these tests.
+pub struct LpTokenIdConvert;
+impl Convert<u32, TokenId> for LpTokenIdConvert {
+	fn convert(pool_id: u32) -> TokenId {
+		1_000_000 + pool_id as u128
+	}
+}
+
 impl Config for Runtime {
 	type Event = ();
 	type Balance = Balance;
 	type PoolId = u32;
 	type TokenId = TokenId;
-	type Currency = Tokens;
+	// `Currencies` dispatches to `pallet_balances` for `GetNativeCurrencyId` and to
+	// `orml_tokens` for every other `TokenId`, so pools can trade native currency too.
+	type Currency = Currencies;
+	type PalletId = FixedSwapPalletId;
+	type LpTokenId = LpTokenIdConvert;
 	type WeightInfo = ();
 }
 
 pub type FixedSwap = Module<Runtime>;
+pub type System = frame_system::Module<Runtime>;
 
 // Build genesis storage according to the mock runtime.
 pub fn new_test_ext() -> sp_io::TestExternalities {
@@ -116,7 +133,61 @@ pub fn new_test_ext() -> sp_io::TestExternalities {
 		endowed_accounts: vec![
 			(0, 1, 100000),
 			(1, 2, 100000),
+			(2, 1, 100000),
+			(2, 2, 100000),
+		],
+	}.assimilate_storage(&mut t).unwrap();
+	pallet_balances::GenesisConfig::<Runtime> {
+		// account_id, initial native balance
+		balances: vec![
+			(0, 1_000_000),
+			(1, 1_000_000),
 		],
 	}.assimilate_storage(&mut t).unwrap();
 	t.into()
 }
+
+/// Global invariants that must hold after any sequence of calls into the pallet. Checked by the
+/// unit tests in `tests.rs` and, after every generated operation, by the `fuzz/` harness.
+pub fn assert_invariants() {
+	use frame_support::storage::{IterableStorageMap, IterableStorageDoubleMap};
+
+	for (pool_id, pool) in crate::Pool::<Runtime>::iter() {
+		if let PoolKind::Fixed = pool.kind {
+			// `total0` is the fixed, never-mutated deposit for this kind, so the amount paid out
+			// can never exceed it.
+			assert!(
+				pool.swapped0 <= pool.total0,
+				"pool {}: swapped0 ({}) exceeds total0 ({})", pool_id, pool.swapped0, pool.total0,
+			);
+
+			// Every fixed-rate swap holds `amount0 = amount1 * total0 / total1` (floor division),
+			// so the running totals can only have drifted below that ratio, never above it.
+			assert!(
+				pool.swapped0 * pool.total1 <= pool.swapped1 * pool.total0,
+				"pool {}: swapped0/swapped1 drifted above the fixed total0/total1 ratio", pool_id,
+			);
+		}
+		// For `ConstantProduct` pools, `total0` is a live reserve that shrinks on every swap, so
+		// `swapped0` legitimately exceeds it once the pool is more than half drained — nothing to
+		// check there beyond the reserve-vs-deposit invariant below.
+
+		let pool_account = FixedSwap::pool_account_id(pool_id);
+		let reserved0 = Currencies::free_balance(pool.token0, &pool_account);
+		assert!(
+			reserved0 <= pool.total0,
+			"pool {}: token0 reserve ({}) exceeds what was ever deposited ({})",
+			pool_id, reserved0, pool.total0,
+		);
+
+		// Every buyer's recorded `Swap` entry is the amount0 they were paid by that pool, so they
+		// must sum back up to the pool's own running total.
+		let buyers_total0: Balance = crate::Swap::<Runtime>::iter_prefix(pool_id)
+			.map(|(_buyer, swap)| swap.0)
+			.fold(0, |acc, amount0| acc + amount0);
+		assert_eq!(
+			buyers_total0, pool.swapped0,
+			"pool {}: buyers' recorded swap amounts don't sum to swapped0", pool_id,
+		);
+	}
+}
@@ -1,20 +1,45 @@
 #![cfg(test)]
 
-use crate::{mock::*, PoolDetails};
-use frame_support::assert_ok;
-use orml_traits::MultiReservableCurrency;
+use crate::{mock::*, Module, PoolDetails, PoolKind};
+use frame_support::{assert_ok, assert_noop};
 use orml_traits::MultiCurrency;
+use sp_runtime::traits::Convert;
+
+fn pool_account(pool_id: u32) -> u64 {
+	Module::<Runtime>::pool_account_id(pool_id)
+}
+
+fn lp_token(pool_id: u32) -> TokenId {
+	LpTokenIdConvert::convert(pool_id)
+}
 
 fn create_pool() {
+	create_pool_with_cap(None);
+}
+
+fn create_pool_with_cap(max_alloc_per_account: Option<Balance>) {
+	let creator = 0;
+	let name = b"swap".to_vec();
+	let token0 = 1;
+	let token1 = 2;
+	let total0 = 100_000;
+	let total1 = 200_000;
+	let duration = 50;
+	assert_ok!(FixedSwap::create(
+		Origin::signed(creator), name, token0, token1, total0, total1, duration, PoolKind::Fixed, 0,
+		max_alloc_per_account,
+	));
+}
+
+fn create_constant_product_pool(total0: Balance, total1: Balance, fee_bps: u32) {
 	let creator = 0;
 	let name = b"swap".to_vec();
 	let token0 = 1;
 	let token1 = 2;
-	let total0 = 100;
-	let total1 = 200;
 	let duration = 50;
 	assert_ok!(FixedSwap::create(
-		Origin::signed(creator), name, token0, token1, total0, total1, duration
+		Origin::signed(creator), name, token0, token1, total0, total1, duration,
+		PoolKind::ConstantProduct, fee_bps, None,
 	));
 }
 
@@ -25,25 +50,27 @@ fn create_works() {
 		let name = b"swap".to_vec();
 		let token0 = 1;
 		let token1 = 2;
-		let total0 = 100;
-		let total1 = 200;
+		let total0 = 100_000;
+		let total1 = 200_000;
 		let swapped0 = 0;
 		let swapped1 = 0;
 		let duration = 50;
 		let start_at = 0;
 		let end_at = 50;
-		assert_eq!(Tokens::total_issuance(token0), 100000);
-		assert_eq!(Tokens::can_reserve(token0, &creator, 100000), true);
-		assert_eq!(Tokens::reserved_balance(token0, &creator), 0);
+		assert_eq!(Tokens::total_issuance(token0), 200000);
 		let pool_id = 0;
 		let pool = PoolDetails {
-			name, creator, token0, token1, total0, total1, swapped0, swapped1, duration, start_at
+			name, creator, token0, token1, total0, total1, swapped0, swapped1, duration, start_at,
+			kind: PoolKind::Fixed, fee_bps: 0, max_alloc_per_account: None,
 		};
 		create_pool();
 		assert_eq!(FixedSwap::pools(pool_id), pool);
 		assert_eq!(FixedSwap::pool_end_at(end_at, pool_id), Some(()));
 		assert_eq!(FixedSwap::next_pool_id(), 1);
-		assert_eq!(Tokens::reserved_balance(token0, &creator), 100);
+		assert_eq!(Tokens::total_balance(token0, &pool_account(pool_id)), 100_000);
+		assert_eq!(Tokens::total_balance(token0, &creator), 0);
+		assert_eq!(FixedSwap::shares(pool_id), 100_000);
+		assert_eq!(Tokens::total_balance(lp_token(pool_id), &creator), 99_000);
 	});
 }
 
@@ -51,51 +78,213 @@ fn create_works() {
 fn swap_works() {
 	new_test_ext().execute_with(|| {
 		create_pool();
-		let creator = 0;
 		let buyer = 1;
 		let pool_id = 0;
-		let amount1 = 20;
+		let amount1 = 20_000;
 		let token0 = 1;
 		let token1 = 2;
 
-		assert_ok!(FixedSwap::swap(Origin::signed(buyer), pool_id, amount1));
+		assert_ok!(FixedSwap::swap(Origin::signed(buyer), pool_id, amount1, 0));
 		let pool = FixedSwap::pools(pool_id);
-		assert_eq!(pool.swapped0, 10);
-		assert_eq!(pool.swapped1, 20);
-		assert_eq!(Tokens::reserved_balance(token0, &creator), 90);
-		assert_eq!(Tokens::total_balance(token0, &buyer), 10);
-		assert_eq!(Tokens::total_balance(token1, &creator), 20);
-		assert_eq!(FixedSwap::swaps(pool_id, buyer), (10, 20));
+		assert_eq!(pool.swapped0, 10_000);
+		assert_eq!(pool.swapped1, 20_000);
+		assert_eq!(Tokens::total_balance(token0, &pool_account(pool_id)), 90_000);
+		assert_eq!(Tokens::total_balance(token0, &buyer), 10_000);
+		assert_eq!(Tokens::total_balance(token1, &pool_account(pool_id)), 20_000);
+		assert_eq!(FixedSwap::swaps(pool_id, buyer), (10_000, 20_000));
 	});
 }
 
 #[test]
-fn auto_payout_works() {
+fn swap_constant_product_works() {
+	new_test_ext().execute_with(|| {
+		let buyer = 1;
+		let pool_id = 0;
+		let token0 = 1;
+
+		create_constant_product_pool(9_000, 9_000, 0);
+		assert_ok!(FixedSwap::swap(Origin::signed(buyer), pool_id, 1_000, 0));
+
+		let pool = FixedSwap::pools(pool_id);
+		assert_eq!(pool.total0, 8_100);
+		assert_eq!(pool.total1, 10_000);
+		assert_eq!(pool.swapped0, 900);
+		assert_eq!(pool.swapped1, 1_000);
+		assert_eq!(Tokens::total_balance(token0, &buyer), 900);
+	});
+}
+
+#[test]
+fn swap_constant_product_rejects_zero_output() {
+	new_test_ext().execute_with(|| {
+		let buyer = 1;
+		let pool_id = 0;
+
+		create_constant_product_pool(2_000, 1_000_000_000, 0);
+		assert_noop!(
+			FixedSwap::swap(Origin::signed(buyer), pool_id, 1, 0),
+			crate::Error::<Runtime>::InsufficientOutputAmount,
+		);
+	});
+}
+
+#[test]
+fn swap_rejects_slippage() {
 	new_test_ext().execute_with(|| {
 		create_pool();
+		let buyer = 1;
+		let pool_id = 0;
+
+		// amount1=20_000 yields amount0=10_000; demand a floor one above that.
+		assert_noop!(
+			FixedSwap::swap(Origin::signed(buyer), pool_id, 20_000, 10_001),
+			crate::Error::<Runtime>::SlippageExceeded,
+		);
+	});
+}
+
+#[test]
+fn swap_rejects_allocation_above_cap() {
+	new_test_ext().execute_with(|| {
+		create_pool_with_cap(Some(5_000));
+		let buyer = 1;
+		let pool_id = 0;
+
+		// amount1=20_000 would pay out amount0=10_000, above the 5_000 cap.
+		assert_noop!(
+			FixedSwap::swap(Origin::signed(buyer), pool_id, 20_000, 0),
+			crate::Error::<Runtime>::AllocationExceeded,
+		);
+	});
+}
+
+#[test]
+fn swap_works_with_native_currency() {
+	new_test_ext().execute_with(|| {
 		let creator = 0;
 		let buyer = 1;
+		let native = 0;
+		let token1 = 2;
+		let pool_id = 0;
+
+		// Sell native currency (token0) for an orml_tokens asset (token1).
+		assert_ok!(FixedSwap::create(
+			Origin::signed(creator), b"native-sale".to_vec(), native, token1, 100_000, 200_000, 50,
+			PoolKind::Fixed, 0, None,
+		));
+		assert_eq!(Currencies::free_balance(native, &pool_account(pool_id)), 100_000);
+		assert_eq!(Currencies::free_balance(native, &creator), 900_000);
+
+		assert_ok!(FixedSwap::swap(Origin::signed(buyer), pool_id, 20_000, 0));
+		assert_eq!(Currencies::free_balance(native, &buyer), 1_010_000);
+		assert_eq!(Currencies::free_balance(native, &pool_account(pool_id)), 90_000);
+		assert_eq!(Tokens::total_balance(token1, &pool_account(pool_id)), 20_000);
+	});
+}
+
+#[test]
+fn add_liquidity_works() {
+	new_test_ext().execute_with(|| {
+		create_constant_product_pool(100_000, 200_000, 0);
+		let buyer = 1;
+		let provider = 2;
 		let pool_id = 0;
-		let amount1 = 20;
 		let token0 = 1;
 		let token1 = 2;
 
-		assert_ok!(FixedSwap::swap(Origin::signed(buyer), pool_id, amount1));
-		let pool = FixedSwap::pools(pool_id);
-		assert_eq!(pool.swapped0, 10);
-		assert_eq!(pool.swapped1, 20);
-		assert_eq!(Tokens::reserved_balance(token0, &creator), 90);
-		assert_eq!(Tokens::total_balance(token0, &buyer), 10);
-		assert_eq!(Tokens::total_balance(token1, &creator), 20);
-		assert_eq!(FixedSwap::swaps(pool_id, buyer), (10, 20));
-
-		assert_eq!(Tokens::reserved_balance(token0, &creator), 90);
+		// Token1 is only ever escrowed via swaps, so seed the reserve with one before providing
+		// liquidity against both sides.
+		assert_ok!(FixedSwap::swap(Origin::signed(buyer), pool_id, 20_000, 0));
+		assert_eq!(Tokens::total_balance(token0, &pool_account(pool_id)), 90_910);
+		assert_eq!(Tokens::total_balance(token1, &pool_account(pool_id)), 20_000);
+
+		assert_ok!(FixedSwap::add_liquidity(Origin::signed(provider), pool_id, 9_091, 2_000));
+
+		assert_eq!(FixedSwap::pools(pool_id).total0, 100_001);
+		assert_eq!(FixedSwap::pools(pool_id).total1, 222_000);
+		assert_eq!(FixedSwap::shares(pool_id), 110_000);
+		assert_eq!(Tokens::total_balance(lp_token(pool_id), &provider), 10_000);
+		assert_eq!(Tokens::total_balance(token0, &pool_account(pool_id)), 100_001);
+		assert_eq!(Tokens::total_balance(token1, &pool_account(pool_id)), 22_000);
+		assert_eq!(Tokens::total_balance(token0, &provider), 90_909);
+		assert_eq!(Tokens::total_balance(token1, &provider), 98_000);
+	});
+}
+
+#[test]
+fn add_liquidity_rejects_fixed_pool() {
+	new_test_ext().execute_with(|| {
+		create_pool();
+		assert_noop!(
+			FixedSwap::add_liquidity(Origin::signed(2), 0, 50_000, 100_000),
+			crate::Error::<Runtime>::OnlyConstantProductPools,
+		);
+	});
+}
+
+#[test]
+fn invariants_hold_across_create_swap_add_remove_finalize() {
+	new_test_ext().execute_with(|| {
+		create_constant_product_pool(9_000, 9_000, 30);
+		assert_invariants();
+
+		assert_ok!(FixedSwap::swap(Origin::signed(1), 0, 1_000, 0));
+		assert_invariants();
+
+		assert_ok!(FixedSwap::add_liquidity(Origin::signed(2), 0, 500, 500));
+		assert_invariants();
+
+		// `remove_liquidity` only opens up once the pool has closed.
+		System::set_block_number(50);
+		let shares = Tokens::total_balance(lp_token(0), &2);
+		assert_ok!(FixedSwap::remove_liquidity(Origin::signed(2), 0, shares));
+		assert_invariants();
+
 		FixedSwap::on_finalize(50);
-		assert_eq!(Tokens::reserved_balance(token0, &creator), 0);
-		assert_eq!(Tokens::total_balance(token0, &creator), 99990);
-		assert_eq!(Tokens::total_balance(token0, &buyer), 10);
+		assert_invariants();
+	});
+}
+
+#[test]
+fn remove_liquidity_works() {
+	new_test_ext().execute_with(|| {
+		create_constant_product_pool(9_000, 9_000, 0);
+		let creator = 0;
+		let buyer = 1;
+		let pool_id = 0;
+		let token0 = 1;
+		let token1 = 2;
+
+		assert_ok!(FixedSwap::swap(Origin::signed(buyer), pool_id, 1_000, 0));
+		System::set_block_number(50);
+		FixedSwap::on_finalize(50);
+
+		// The creator holds every share that wasn't permanently locked at creation.
+		let creator_shares = Tokens::total_balance(lp_token(pool_id), &creator);
+		assert_eq!(creator_shares, 8_000);
+
+		assert_ok!(FixedSwap::remove_liquidity(Origin::signed(creator), pool_id, creator_shares));
+
+		assert_eq!(Tokens::total_balance(lp_token(pool_id), &creator), 0);
+		assert_eq!(FixedSwap::shares(pool_id), 1_000);
+		assert_eq!(Tokens::total_balance(token0, &creator), 98_200);
+		assert_eq!(Tokens::total_balance(token1, &creator), 888);
+		assert_eq!(Tokens::total_balance(token0, &pool_account(pool_id)), 900);
+		assert_eq!(Tokens::total_balance(token1, &pool_account(pool_id)), 112);
+	});
+}
+
+#[test]
+fn remove_liquidity_rejects_before_pool_closes() {
+	new_test_ext().execute_with(|| {
+		create_constant_product_pool(9_000, 9_000, 0);
+		let creator = 0;
+		let pool_id = 0;
+		let creator_shares = Tokens::total_balance(lp_token(pool_id), &creator);
 
-		assert_eq!(Tokens::total_balance(token1, &creator), 20);
-		assert_eq!(Tokens::total_balance(token1, &buyer), 99980);
+		assert_noop!(
+			FixedSwap::remove_liquidity(Origin::signed(creator), pool_id, creator_shares),
+			crate::Error::<Runtime>::PoolNotClosed,
+		);
 	});
 }